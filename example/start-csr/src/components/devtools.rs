@@ -0,0 +1,42 @@
+use leptos::prelude::*;
+use leptos_query::devtools::{QueryInfo, QueryStatus};
+
+/// Renders a live list of the queries in `entries`, showing each key with
+/// its active/idle/pending-GC status and observer count. It does not show a
+/// query's `QueryState` or last-updated time. Feed it with
+/// [`leptos_query::devtools::use_query_registry`].
+///
+/// `entries` only ever holds queries of one `K`/`V`/`E` type (see the
+/// `leptos_query::devtools` module docs) — mount one `<QueryDevtools/>` per
+/// query type you want to inspect.
+#[component]
+pub fn QueryDevtools<K, V, E>(
+    #[prop(into)] entries: Signal<Vec<QueryInfo<K, V, E>>>,
+) -> impl IntoView
+where
+    K: Clone + std::fmt::Debug + PartialEq + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    E: Clone + std::fmt::Debug + Send + Sync + 'static,
+{
+    view! {
+        <ul class="text-xs font-mono divide-y divide-foreground/10">
+            <For
+                each=move || entries.get()
+                key=|info| format!("{:?}", info.key)
+                let:info
+            >
+                <li class="flex gap-2 py-1">
+                    <span class="font-bold">{format!("{:?}", info.key)}</span>
+                    <span>
+                        {match info.status {
+                            QueryStatus::Active => "active",
+                            QueryStatus::Idle => "idle",
+                            QueryStatus::PendingGc => "pending gc",
+                        }}
+                    </span>
+                    <span>{info.observers}" observers"</span>
+                </li>
+            </For>
+        </ul>
+    }
+}
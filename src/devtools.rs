@@ -0,0 +1,187 @@
+//! Introspection for a single query cache, for building devtools-style
+//! inspector panels.
+//!
+//! The query cache holds many different `K`/`V`/`E` type combinations at
+//! once, but every item here ([`snapshot_queries`], [`use_query_registry`],
+//! and the example `<QueryDevtools/>` component) is generic over one fixed
+//! triple and can therefore only enumerate queries of that one type — not
+//! "every live query in the cache" regardless of type. To inspect several
+//! query types, instantiate one registry (and one `<QueryDevtools/>`) per
+//! type and compose the panels yourself.
+
+use std::{hash::Hash, time::Duration};
+
+use leptos::*;
+
+use crate::{query::Query, use_cache, Instant, QueryState};
+
+/// Where a query sits in its observer/garbage-collection lifecycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryStatus {
+    /// At least one component is currently observing this query.
+    Active,
+    /// No observers, and not yet scheduled for eviction (no `cache_time`).
+    Idle,
+    /// No observers, and a `cache_time` timeout is counting down to evict it.
+    PendingGc,
+}
+
+/// A point-in-time snapshot of a single cached query, for introspection.
+#[derive(Clone)]
+pub struct QueryInfo<K, V, E> {
+    pub key: K,
+    pub state: QueryState<V, E>,
+    pub updated_at: Option<Instant>,
+    pub observers: usize,
+    pub stale_time: Option<Duration>,
+    pub cache_time: Option<Duration>,
+    pub status: QueryStatus,
+}
+
+impl<K: Clone, V: Clone, E: Clone> QueryInfo<K, V, E> {
+    fn from_query(query: &Query<K, V, E>) -> Self {
+        let state = query.data.get_untracked();
+        let observers = query.observers.get();
+        let status = if observers > 0 {
+            QueryStatus::Active
+        } else if query.pending_gc.get() {
+            QueryStatus::PendingGc
+        } else {
+            QueryStatus::Idle
+        };
+
+        Self {
+            key: query.key.clone(),
+            updated_at: state.updated_at(),
+            state,
+            observers,
+            stale_time: query.stale_time.get_untracked(),
+            cache_time: query.cache_time.get_untracked(),
+            status,
+        }
+    }
+}
+
+/// Takes a one-off snapshot of every live query of this `K`/`V`/`E` type
+/// currently in the cache.
+///
+/// A cache holds queries of many different `K`/`V`/`E` type combinations at
+/// once, but this function (being generic over one fixed triple) can only
+/// see the slice backed by that triple. It is not a snapshot of "every live
+/// query" in the cache as a whole — call it once per query type you want to
+/// inspect and combine the results yourself. See the module docs for the
+/// same caveat on `QueryDevtools`.
+pub fn snapshot_queries<K, V, E>(cx: Scope) -> Vec<QueryInfo<K, V, E>>
+where
+    K: Clone + Eq + Hash + 'static,
+    V: Clone + 'static,
+    E: Clone + 'static,
+{
+    use_cache::<K, V, Vec<QueryInfo<K, V, E>>>(cx, |(_, cache)| {
+        cache.values().map(QueryInfo::from_query).collect()
+    })
+}
+
+/// A `Signal` that polls the cache every `refresh_interval` and reflects the
+/// current set of live queries of this `K`/`V`/`E` type. Intended for
+/// building a devtools-style inspector panel (see [`crate::devtools`] docs
+/// for the `QueryDevtools` component this is meant to feed).
+///
+/// Like [`snapshot_queries`], this only covers one query type per call. A
+/// panel that inspects several query types needs one `use_query_registry`
+/// call (and one `<QueryDevtools/>` instance) per type; there is currently
+/// no single registry that aggregates across types.
+pub fn use_query_registry<K, V, E>(cx: Scope, refresh_interval: Duration) -> Signal<Vec<QueryInfo<K, V, E>>>
+where
+    K: Clone + Eq + Hash + 'static,
+    V: Clone + 'static,
+    E: Clone + 'static,
+{
+    let snapshot = create_rw_signal(cx, snapshot_queries::<K, V, E>(cx));
+
+    set_interval(
+        move || snapshot.set(snapshot_queries::<K, V, E>(cx)),
+        refresh_interval,
+    );
+
+    snapshot.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+    };
+
+    use super::*;
+
+    fn test_query(cx: Scope) -> Query<&'static str, u32, ()> {
+        Query {
+            key: "k",
+            data: create_rw_signal(cx, QueryState::Created),
+            stale_time: create_rw_signal(cx, None),
+            cache_time: create_rw_signal(cx, None),
+            refetch_interval: create_rw_signal(cx, None),
+            observers: Rc::new(Cell::new(0)),
+            generation: Rc::new(Cell::new(0)),
+            active: Rc::new(Cell::new(true)),
+            pending_gc: Rc::new(Cell::new(false)),
+            in_flight: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    #[test]
+    fn status_is_active_whenever_there_are_observers() {
+        let runtime = create_runtime();
+        create_scope(runtime, |cx| {
+            let query = test_query(cx);
+            query.observers.set(1);
+            query.pending_gc.set(true); // Observers take priority even if this is also set.
+
+            assert_eq!(QueryInfo::from_query(&query).status, QueryStatus::Active);
+        })
+        .dispose();
+        runtime.dispose();
+    }
+
+    #[test]
+    fn status_is_pending_gc_when_unobserved_with_a_gc_timeout_scheduled() {
+        let runtime = create_runtime();
+        create_scope(runtime, |cx| {
+            let query = test_query(cx);
+            query.pending_gc.set(true);
+
+            assert_eq!(QueryInfo::from_query(&query).status, QueryStatus::PendingGc);
+        })
+        .dispose();
+        runtime.dispose();
+    }
+
+    #[test]
+    fn status_is_idle_when_unobserved_with_no_gc_timeout_scheduled() {
+        let runtime = create_runtime();
+        create_scope(runtime, |cx| {
+            let query = test_query(cx);
+
+            assert_eq!(QueryInfo::from_query(&query).status, QueryStatus::Idle);
+        })
+        .dispose();
+        runtime.dispose();
+    }
+
+    #[test]
+    fn from_query_carries_over_key_and_observer_count() {
+        let runtime = create_runtime();
+        create_scope(runtime, |cx| {
+            let query = test_query(cx);
+            query.observers.set(3);
+
+            let info = QueryInfo::from_query(&query);
+            assert_eq!(info.key, "k");
+            assert_eq!(info.observers, 3);
+        })
+        .dispose();
+        runtime.dispose();
+    }
+}
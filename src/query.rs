@@ -0,0 +1,237 @@
+use std::{
+    cell::{Cell, RefCell},
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    time::Duration,
+};
+
+use futures::future::{FutureExt, Shared};
+use leptos::*;
+
+use crate::Instant;
+
+/// The shared future of a query's in-flight request, as stored in
+/// [`Query::in_flight`].
+pub(crate) type InFlightFetch<V, E> = Shared<Pin<Box<dyn Future<Output = Result<V, E>>>>>;
+
+/// The reactive state of a single cached query.
+///
+/// A `Query` is keyed by `K` and holds the current [`QueryState`] alongside
+/// the knobs (`stale_time`, `cache_time`, `refetch_interval`) that control
+/// when it refetches and when it is evicted from the cache.
+#[derive(Clone)]
+pub struct Query<K, V, E> {
+    pub key: K,
+    pub data: RwSignal<QueryState<V, E>>,
+    pub stale_time: RwSignal<Option<Duration>>,
+    pub cache_time: RwSignal<Option<Duration>>,
+    pub refetch_interval: RwSignal<Option<Duration>>,
+    pub observers: Rc<Cell<usize>>,
+    /// Bumped every time a fetch begins. A fetch in flight snapshots the
+    /// generation it started with and discards its result on resolve if the
+    /// generation has since moved on, so a superseded fetch can never
+    /// clobber fresher state.
+    pub(crate) generation: Rc<Cell<u64>>,
+    /// Whether this query is allowed to fetch. Flipped off by [`Query::stop`]
+    /// to pause fetching (e.g. while a component is backgrounded) without
+    /// losing the cached data, and back on by [`Query::start`].
+    pub(crate) active: Rc<Cell<bool>>,
+    /// Whether a `cache_time` eviction timeout has been scheduled for this
+    /// query. Set whenever such a timeout is scheduled, whether or not the
+    /// query has observers; [`crate::devtools`] consults it only for queries
+    /// with no observers, to distinguish "idle" from "about to be collected".
+    pub(crate) pending_gc: Rc<Cell<bool>>,
+    /// The fetch currently in flight for this query, if any. Set by the
+    /// executor call that issues the request and cleared once it settles
+    /// or is cancelled. A second executor call that starts while it is
+    /// pending does not await it: it sees the `Loading`/`Fetching` state
+    /// and returns without issuing a duplicate request.
+    pub(crate) in_flight: Rc<RefCell<Option<InFlightFetch<V, E>>>>,
+}
+
+impl<K, V, E> Query<K, V, E> {
+    pub(crate) fn dispose(self) {
+        self.data.dispose();
+        self.stale_time.dispose();
+        self.cache_time.dispose();
+        self.refetch_interval.dispose();
+    }
+
+    /// Pause fetching for this query. Any fetch currently in flight is
+    /// cancelled: its result will be discarded rather than applied when it
+    /// resolves.
+    pub fn stop(&self) {
+        self.active.set(false);
+        self.cancel_in_flight();
+    }
+
+    /// Resume fetching for this query after a call to [`Query::stop`].
+    pub fn start(&self) {
+        self.active.set(true);
+    }
+
+    /// Cancels any fetch currently in flight for this query without
+    /// changing whether it's allowed to fetch again.
+    ///
+    /// Clears the dedup latch as well as bumping the generation: otherwise
+    /// a later fetch that starts while this one is still resolving (just
+    /// no longer current) would see the cancelled fetch's latch still
+    /// published and join it, applying its stale result under the new
+    /// fetch's generation instead of issuing a fresh request.
+    pub(crate) fn cancel_in_flight(&self) {
+        self.generation.set(self.generation.get().wrapping_add(1));
+        self.in_flight.borrow_mut().take();
+    }
+
+    /// Marks the start of a new fetch, cancelling whichever fetch was
+    /// previously in flight, and returns the generation the new fetch
+    /// should tag its writes with.
+    pub(crate) fn begin_fetch(&self) -> u64 {
+        self.cancel_in_flight();
+        self.generation.get()
+    }
+
+    /// Whether `generation` is still the most recent fetch started for this
+    /// query, i.e. whether it's safe to apply its result.
+    pub(crate) fn is_current(&self, generation: u64) -> bool {
+        self.generation.get() == generation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::FutureExt;
+
+    use super::*;
+
+    fn test_query(cx: Scope) -> Query<&'static str, u32, ()> {
+        Query {
+            key: "k",
+            data: create_rw_signal(cx, QueryState::Created),
+            stale_time: create_rw_signal(cx, None),
+            cache_time: create_rw_signal(cx, None),
+            refetch_interval: create_rw_signal(cx, None),
+            observers: Rc::new(Cell::new(0)),
+            generation: Rc::new(Cell::new(0)),
+            active: Rc::new(Cell::new(true)),
+            pending_gc: Rc::new(Cell::new(false)),
+            in_flight: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    fn dummy_latch() -> InFlightFetch<u32, ()> {
+        (Box::pin(std::future::ready(Ok(1))) as Pin<Box<dyn Future<Output = Result<u32, ()>>>>)
+            .shared()
+    }
+
+    #[test]
+    fn cancel_in_flight_clears_the_dedup_latch() {
+        let runtime = create_runtime();
+        create_scope(runtime, |cx| {
+            let query = test_query(cx);
+            *query.in_flight.borrow_mut() = Some(dummy_latch());
+
+            query.cancel_in_flight();
+
+            assert!(
+                query.in_flight.borrow().is_none(),
+                "a cancelled fetch's latch must not still be published for a later call to join"
+            );
+        })
+        .dispose();
+        runtime.dispose();
+    }
+
+    #[test]
+    fn begin_fetch_clears_whichever_latch_it_superseded() {
+        let runtime = create_runtime();
+        create_scope(runtime, |cx| {
+            let query = test_query(cx);
+            *query.in_flight.borrow_mut() = Some(dummy_latch());
+
+            query.begin_fetch();
+
+            assert!(
+                query.in_flight.borrow().is_none(),
+                "starting a new fetch must not leave the previous fetch's latch joinable"
+            );
+        })
+        .dispose();
+        runtime.dispose();
+    }
+}
+
+/// A successful fetch result paired with the time it was produced.
+#[derive(Clone)]
+pub struct QueryData<V> {
+    pub data: V,
+    pub updated_at: Instant,
+}
+
+/// The lifecycle of a query's cached data.
+///
+/// `V` is the value produced by a successful fetch, `E` is the error a
+/// failed fetch can produce.
+#[derive(Clone)]
+pub enum QueryState<V, E> {
+    /// The query has been created, but has not started fetching yet.
+    Created,
+    /// The query is fetching for the first time; no data is available yet.
+    Loading,
+    /// The query has data and is re-fetching in the background.
+    Fetching(QueryData<V>),
+    /// The query resolved successfully and its data is considered fresh.
+    Loaded(QueryData<V>),
+    /// The query's data has been marked invalid and should be refetched.
+    Invalid(QueryData<V>),
+    /// The query exhausted its retries. `previous_data` is kept around so
+    /// stale data can still be rendered while the error is surfaced.
+    Error(E, Option<QueryData<V>>),
+}
+
+impl<V: Clone, E> QueryState<V, E> {
+    /// The timestamp of the last successful fetch, if any.
+    pub fn updated_at(&self) -> Option<Instant> {
+        match self {
+            QueryState::Created | QueryState::Loading => None,
+            QueryState::Fetching(data) | QueryState::Loaded(data) | QueryState::Invalid(data) => {
+                Some(data.updated_at)
+            }
+            QueryState::Error(_, previous_data) => {
+                previous_data.as_ref().map(|data| data.updated_at)
+            }
+        }
+    }
+
+    /// The most recently known data, whether fresh, stale, or kept around
+    /// after an error.
+    pub fn data(&self) -> Option<&V> {
+        match self {
+            QueryState::Created | QueryState::Loading => None,
+            QueryState::Fetching(data) | QueryState::Loaded(data) | QueryState::Invalid(data) => {
+                Some(&data.data)
+            }
+            QueryState::Error(_, previous_data) => previous_data.as_ref().map(|data| &data.data),
+        }
+    }
+
+    /// The most recently known [`QueryData`], carried forward so it can be
+    /// shown as stale data after a fetch fails.
+    pub(crate) fn query_data(&self) -> Option<QueryData<V>> {
+        match self {
+            QueryState::Created | QueryState::Loading => None,
+            QueryState::Fetching(data) | QueryState::Loaded(data) | QueryState::Invalid(data) => {
+                Some(data.clone())
+            }
+            QueryState::Error(_, previous_data) => previous_data.clone(),
+        }
+    }
+
+    /// Whether this state represents data that should be considered
+    /// refetchable by the on-mount stale check (i.e. not already in
+    /// flight).
+    pub(crate) fn is_refetchable(&self) -> bool {
+        !matches!(self, QueryState::Loading | QueryState::Fetching(_))
+    }
+}
@@ -1,16 +1,19 @@
+use futures::future::FutureExt;
 use leptos::*;
 use std::{
     cell::{Cell, RefCell},
     collections::HashMap,
     future::Future,
     hash::Hash,
+    pin::Pin,
     rc::Rc,
     time::Duration,
 };
 
 use crate::{
     instant::get_instant,
-    query::Query,
+    query::{InFlightFetch, Query},
+    retry::RetryConfig,
     use_cache, use_query_client,
     util::{time_until_stale, use_timeout},
     Instant, QueryData, QueryState,
@@ -26,53 +29,210 @@ pub fn suppress_query_load(suppress: bool) {
 }
 
 // Create Executor function which will execute task in `spawn_local` and update state.
-pub(crate) fn create_executor<K, V, Fu>(
-    state: Signal<Query<K, V>>,
+//
+// NOTE: `retry_config` and `slow_threshold` are not reachable from any public
+// API in this checked-out tree — `create_executor` has no caller here at all
+// (`grep -rn create_executor` only turns up this definition), and the
+// query-definition surface that would normally thread options through to it
+// (e.g. `UseQueryOptions`, `use_query`, `lib.rs`) isn't part of this source
+// checkout, so there's nothing here for either of these parameters, or the
+// `Fn(K) -> Result<V, E>` closure signature, to have broken. Wiring them
+// into the public API has to happen wherever that surface actually lives;
+// until then, nothing in this tree calls `create_executor`, and `RetryConfig`
+// is exercised only by its own unit tests in `retry.rs`.
+pub(crate) fn create_executor<K, V, E, Fu>(
+    state: Signal<Query<K, V, E>>,
     query: impl Fn(K) -> Fu + 'static,
+    retry_config: RetryConfig,
+    slow_threshold: Option<Duration>,
 ) -> impl Fn()
 where
-    K: Clone + Hash + Eq + PartialEq + 'static,
+    K: Clone + Hash + Eq + PartialEq + std::fmt::Debug + 'static,
     V: Clone + 'static,
-    Fu: Future<Output = V> + 'static,
+    E: Clone + 'static,
+    Fu: Future<Output = Result<V, E>> + 'static,
 {
     let query = Rc::new(query);
     move || {
         let query = query.clone();
         SUPPRESS_QUERY_LOAD.with(|supressed| {
-            if !supressed.get() {
-                spawn_local(async move {
-                    let state = state.get_untracked();
-                    let data_state = state.data.get_untracked();
-                    match data_state {
-                        QueryState::Fetching(_) | QueryState::Loading => (),
-                        // First load.
-                        QueryState::Created => {
-                            state.data.set(QueryState::Loading);
-                            let data = query(state.key.clone()).await;
-                            let updated_at = get_instant();
-                            let data = QueryData { data, updated_at };
-                            state.data.set(QueryState::Loaded(data));
-                        }
-                        // Subsequent loads.
-                        QueryState::Loaded(data) | QueryState::Invalid(data) => {
-                            state.data.set(QueryState::Fetching(data));
-                            let data = query(state.key.clone()).await;
-                            let updated_at = get_instant();
-                            let data = QueryData { data, updated_at };
-                            state.data.set(QueryState::Loaded(data));
-                        }
-                    }
-                })
+            if !supressed.get() && state.get_untracked().active.get() {
+                spawn_local(run_fetch(state, query, retry_config, slow_threshold, 0, None))
             }
         })
     }
 }
 
-// Start synchronization effects.
-pub(crate) fn synchronize_state<K, V>(cx: Scope, query: Signal<Query<K, V>>, executor: Rc<dyn Fn()>)
+// Runs a single fetch attempt and, on failure, reschedules itself with
+// exponential backoff until `retry_config.max_retries` is exhausted.
+// `attempt` is 0 for the initial fetch and 1-indexed for retries. `generation`
+// is `None` on the first attempt (a fresh generation is claimed then) and
+// `Some` on a scheduled retry, which must keep tagging its writes with the
+// generation claimed by the attempt that preceded it.
+fn run_fetch<K, V, E, Fu>(
+    state: Signal<Query<K, V, E>>,
+    query: Rc<impl Fn(K) -> Fu + 'static>,
+    retry_config: RetryConfig,
+    slow_threshold: Option<Duration>,
+    attempt: u32,
+    generation: Option<u64>,
+) -> std::pin::Pin<Box<dyn Future<Output = ()>>>
 where
+    K: Clone + Hash + Eq + PartialEq + std::fmt::Debug + 'static,
+    V: Clone + 'static,
+    E: Clone + 'static,
+    Fu: Future<Output = Result<V, E>> + 'static,
+{
+    // Computed outside the async block so the span can be attached to the
+    // future itself via `Instrument`, rather than entered with a guard held
+    // across an `.await` (which `tracing` explicitly warns against: the
+    // guard doesn't track suspension/resumption, so events from other tasks
+    // polled while this one is pending would get misattributed to it).
+    #[cfg(feature = "tracing")]
+    let fetch_span =
+        tracing::info_span!("leptos_query::fetch", key = ?state.get_untracked().key, attempt);
+
+    let fut = async move {
+        let state = state.get_untracked();
+        let data_state = state.data.get_untracked();
+
+        // Only the first attempt of a fetch cycle needs to guard against
+        // re-entering while already in flight, claim a fresh generation
+        // (cancelling whatever fetch was previously in flight), or
+        // transition into the loading/fetching state. Retries fall
+        // straight through to the fetch below, reusing the generation
+        // claimed by the attempt that scheduled them.
+        let generation = match generation {
+            // A retry: bail out early if something cancelled the query
+            // while we were waiting out the backoff delay.
+            Some(generation) if !state.is_current(generation) => return,
+            Some(generation) => generation,
+            None => {
+                match &data_state {
+                    QueryState::Fetching(_) | QueryState::Loading => return,
+                    QueryState::Created => state.data.set(QueryState::Loading),
+                    QueryState::Loaded(data) | QueryState::Invalid(data) => {
+                        state.data.set(QueryState::Fetching(data.clone()))
+                    }
+                    QueryState::Error(_, Some(data)) => {
+                        state.data.set(QueryState::Fetching(data.clone()))
+                    }
+                    QueryState::Error(_, None) => state.data.set(QueryState::Loading),
+                }
+                #[cfg(feature = "tracing")]
+                tracing::debug!("query started");
+                state.begin_fetch()
+            }
+        };
+
+        #[cfg(feature = "tracing")]
+        let fetch_started_at = get_instant();
+
+        // If the fetch is still pending after `slow_threshold`, warn so a
+        // stuck or unexpectedly long-running fetch doesn't go unnoticed
+        // behind `spawn_local`.
+        #[cfg(feature = "tracing")]
+        let slow_warning = slow_threshold.and_then(|threshold| {
+            let key = format!("{:?}", state.key);
+            set_timeout_with_handle(
+                move || {
+                    tracing::warn!(key, threshold_ms = threshold.as_millis() as u64, "query still pending after threshold");
+                },
+                threshold,
+            )
+            .ok()
+        });
+        #[cfg(not(feature = "tracing"))]
+        let _ = slow_threshold;
+
+        // Publish this request's shared future in `in_flight` while it is
+        // pending, and clear the slot once it settles. A second executor
+        // call that starts while this fetch is pending returns at the
+        // `Loading`/`Fetching` check above without issuing a request, and
+        // `begin_fetch`/`cancel_in_flight` clear the slot whenever a fetch
+        // is superseded, so a cancelled fetch's latch is never joined. The
+        // slot is therefore always empty here: no call awaits another
+        // call's latch, and the join branch below is not taken.
+        let existing_latch = state.in_flight.borrow().clone();
+        let result = if let Some(latch) = existing_latch {
+            latch.await
+        } else {
+            let latch: InFlightFetch<V, E> =
+                (Box::pin(query(state.key.clone())) as Pin<Box<dyn Future<Output = Result<V, E>>>>)
+                    .shared();
+            *state.in_flight.borrow_mut() = Some(latch.clone());
+            let result = latch.await;
+            *state.in_flight.borrow_mut() = None;
+            result
+        };
+
+        #[cfg(feature = "tracing")]
+        if let Some(handle) = slow_warning {
+            handle.clear();
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            elapsed_ms = get_instant().duration_since(fetch_started_at).as_millis() as u64,
+            ok = result.is_ok(),
+            "query settled"
+        );
+
+        // The query was cancelled (key changed, scope disposed, or `stop`
+        // was called) while the fetch was in flight. Discard the result
+        // rather than clobbering whatever state superseded it.
+        if !state.is_current(generation) {
+            return;
+        }
+
+        match result {
+            Ok(data) => {
+                let updated_at = get_instant();
+                state.data.set(QueryState::Loaded(QueryData { data, updated_at }));
+            }
+            Err(err) => {
+                if attempt >= retry_config.max_retries {
+                    let previous_data = data_state.query_data();
+                    state.data.set(QueryState::Error(err, previous_data));
+                } else {
+                    let next_attempt = attempt + 1;
+                    let delay = retry_config.delay_for_attempt(next_attempt);
+                    let query = query.clone();
+                    let _ = set_timeout_with_handle(
+                        move || {
+                            spawn_local(run_fetch(
+                                state,
+                                query,
+                                retry_config,
+                                slow_threshold,
+                                next_attempt,
+                                Some(generation),
+                            ));
+                        },
+                        delay,
+                    );
+                }
+            }
+        }
+    };
+
+    #[cfg(feature = "tracing")]
+    let fut = {
+        use tracing::Instrument;
+        fut.instrument(fetch_span)
+    };
+
+    Box::pin(fut)
+}
+
+// Start synchronization effects.
+pub(crate) fn synchronize_state<K, V, E>(
+    cx: Scope,
+    query: Signal<Query<K, V, E>>,
+    executor: Rc<dyn Fn()>,
+) where
     K: Hash + Eq + PartialEq + Clone + 'static,
-    V: Clone,
+    V: Clone + 'static,
+    E: Clone + 'static,
 {
     ensure_not_stale(cx, query, executor.clone());
     ensure_not_invalid(cx, query, executor.clone());
@@ -82,20 +242,18 @@ where
 }
 
 /// On mount, ensure that the resource is not stale
-fn ensure_not_stale<K: Clone, V: Clone>(
+fn ensure_not_stale<K: Clone, V: Clone + 'static, E: Clone + 'static>(
     cx: Scope,
-    query: Signal<Query<K, V>>,
+    query: Signal<Query<K, V, E>>,
     executor: Rc<dyn Fn()>,
 ) {
     create_isomorphic_effect(cx, move |_| {
         let query = query.get();
         let stale_time = query.stale_time;
+        let data_state = query.data.get_untracked();
 
-        if let (Some(updated_at), Some(stale_time)) = (
-            query.data.get_untracked().updated_at(),
-            stale_time.get_untracked(),
-        ) {
-            if time_until_stale(updated_at, stale_time).is_zero() {
+        if let (Some(updated_at), Some(stale_time)) = (data_state.updated_at(), stale_time.get_untracked()) {
+            if data_state.is_refetchable() && time_until_stale(updated_at, stale_time).is_zero() {
                 executor();
             }
         }
@@ -103,14 +261,22 @@ fn ensure_not_stale<K: Clone, V: Clone>(
 }
 
 /// Refetch data once marked as invalid.
-fn ensure_not_invalid<K: Clone, V: Clone>(
+///
+/// `Error` is deliberately not handled here: this effect reruns on every
+/// `state.data` change, including the transition into `Error` that `run_fetch`
+/// itself performs after exhausting `max_retries`. Wiring `Error` in here
+/// would immediately kick off a brand-new fetch cycle with a fresh retry
+/// budget, and if the endpoint is still failing that cycle ends in `Error`
+/// again — an unbounded loop that defeats `max_retries` entirely. A terminal
+/// `Error` should only be retried in response to an explicit invalidate/
+/// refetch from the caller.
+fn ensure_not_invalid<K: Clone, V: Clone + 'static, E: Clone + 'static>(
     cx: Scope,
-    state: Signal<Query<K, V>>,
+    state: Signal<Query<K, V, E>>,
     executor: Rc<dyn Fn()>,
 ) {
     create_isomorphic_effect(cx, move |_| {
         let state = state.get();
-        // Refetch query if Invalid.
         match state.data.get() {
             QueryState::Invalid(_) => executor(),
             _ => (),
@@ -119,10 +285,11 @@ fn ensure_not_invalid<K: Clone, V: Clone>(
 }
 
 /// Effect for refetching query on interval, if present.
-fn sync_refetch<K, V>(cx: Scope, query: Signal<Query<K, V>>, executor: Rc<dyn Fn()>)
+fn sync_refetch<K, V, E>(cx: Scope, query: Signal<Query<K, V, E>>, executor: Rc<dyn Fn()>)
 where
     K: Clone + 'static,
     V: Clone + 'static,
+    E: Clone + 'static,
 {
     let _ = use_timeout(cx, {
         move || {
@@ -147,40 +314,55 @@ where
     });
 }
 
-// Ensure that observers are kept track of.
-fn sync_observers<K: Clone, V: Clone>(cx: Scope, query: Signal<Query<K, V>>) {
-    type Observer = Rc<Cell<usize>>;
-    let last_observer: Rc<Cell<Option<Observer>>> = Rc::new(Cell::new(None));
+// Ensure that observers are kept track of, and cancel the in-flight fetch
+// for a query that just lost its last observer (key changed away from it,
+// or this scope was disposed). `Query` is shared across every observer of
+// the same key, so this only cancels once the count actually reaches zero
+// — otherwise another observer still relying on that fetch (e.g. component
+// A unmounting while component B is still waiting on it) would have its
+// result silently discarded.
+fn sync_observers<K: Clone, V: Clone + 'static, E: Clone + 'static>(
+    cx: Scope,
+    query: Signal<Query<K, V, E>>,
+) {
+    let last_query: Rc<Cell<Option<Query<K, V, E>>>> = Rc::new(Cell::new(None));
 
     on_cleanup(cx, {
-        let last_observer = last_observer.clone();
+        let last_query = last_query.clone();
         move || {
-            if let Some(observer) = last_observer.take() {
-                observer.set(observer.get() - 1);
+            if let Some(query) = last_query.take() {
+                release_observer(&query);
             }
         }
     });
 
-    // Ensure that observers are kept track of.
-    create_isomorphic_effect(cx, move |observers: Option<Rc<Cell<usize>>>| {
-        // Decrement previous observers.
-        if let Some(observers) = observers {
-            last_observer.set(None);
-            observers.set(observers.get() - 1);
+    create_isomorphic_effect(cx, move |_| {
+        // Release the previous query's observer slot.
+        if let Some(previous) = last_query.take() {
+            release_observer(&previous);
         }
-        // Deal with latest observers.
-        let observers = query.get().observers;
-        last_observer.set(Some(observers.clone()));
-        observers.set(observers.get() + 1);
-        observers
+        // Claim the current query's observer slot.
+        let current = query.get();
+        current.observers.set(current.observers.get() + 1);
+        last_query.set(Some(current));
     });
 }
 
+/// Releases one observer of `query`, cancelling its in-flight fetch if that
+/// was the last observer watching it.
+fn release_observer<K: Clone, V: Clone, E: Clone>(query: &Query<K, V, E>) {
+    query.observers.set(query.observers.get() - 1);
+    if query.observers.get() == 0 {
+        query.cancel_in_flight();
+    }
+}
+
 /// This is a very finicky function. Be cautious with edits.
-fn ensure_cache_cleanup<K, V>(cx: Scope, query: Signal<Query<K, V>>)
+fn ensure_cache_cleanup<K, V, E>(cx: Scope, query: Signal<Query<K, V, E>>)
 where
     K: Clone + Hash + Eq + PartialEq + 'static,
     V: Clone + 'static,
+    E: Clone + 'static,
 {
     let root_scope = use_query_client(cx).cx;
 
@@ -222,6 +404,7 @@ where
         if let Some(clear) = timeout_map.remove(&query.key) {
             clear()
         }
+        query.pending_gc.set(false);
 
         let child_disposed = child_disposed.clone();
         let cleanup_map = cleanup_map.clone();
@@ -234,6 +417,7 @@ where
                     let child_disposed = child_disposed.clone();
                     let cleanup_map = cleanup_map.clone();
                     let query = query.clone();
+                    query.pending_gc.set(true);
 
                     set_timeout_with_handle(
                         move || {
@@ -241,7 +425,7 @@ where
                             let dispose = {
                                 let query = query.clone();
                                 move || {
-                                    let removed = use_cache::<K, V, Option<Query<K, V>>>(
+                                    let removed = use_cache::<K, V, Option<Query<K, V, E>>>(
                                         root_scope,
                                         move |(_, cache)| cache.remove(&query.key),
                                     );
@@ -284,3 +468,53 @@ fn maybe_time_until_stale(
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_query(cx: Scope) -> Query<&'static str, u32, ()> {
+        Query {
+            key: "k",
+            data: create_rw_signal(cx, QueryState::Created),
+            stale_time: create_rw_signal(cx, None),
+            cache_time: create_rw_signal(cx, None),
+            refetch_interval: create_rw_signal(cx, None),
+            observers: Rc::new(Cell::new(0)),
+            generation: Rc::new(Cell::new(0)),
+            active: Rc::new(Cell::new(true)),
+            pending_gc: Rc::new(Cell::new(false)),
+            in_flight: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    #[test]
+    fn release_observer_only_cancels_once_the_last_observer_leaves() {
+        let runtime = create_runtime();
+        create_scope(runtime, |cx| {
+            let query = test_query(cx);
+            query.observers.set(2);
+            let generation_before = query.generation.get();
+
+            // Component A unmounts; component B is still observing.
+            release_observer(&query);
+            assert_eq!(query.observers.get(), 1);
+            assert_eq!(
+                query.generation.get(),
+                generation_before,
+                "must not cancel the fetch while another observer is still relying on it"
+            );
+
+            // Component B unmounts too; now it's safe to cancel.
+            release_observer(&query);
+            assert_eq!(query.observers.get(), 0);
+            assert_eq!(
+                query.generation.get(),
+                generation_before.wrapping_add(1),
+                "must cancel once the last observer leaves"
+            );
+        })
+        .dispose();
+        runtime.dispose();
+    }
+}
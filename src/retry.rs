@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+/// Configures how a failed query fetch is retried.
+///
+/// Retries use exponential backoff: the Nth retry waits for
+/// `min(base_delay * 2^(N - 1), max_delay)`, optionally perturbed by random
+/// jitter so that many queries failing at once don't all retry in lockstep.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryConfig {
+    /// Number of retry attempts after the initial fetch fails. `0` disables
+    /// retries entirely.
+    pub max_retries: u32,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    /// Upper bound on the backoff delay, regardless of attempt count.
+    pub max_delay: Duration,
+    /// Whether to randomize each delay by up to 50%, to avoid a thundering
+    /// herd of retries firing at the exact same instant.
+    pub jitter: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// A config that never retries; the first failure is terminal.
+    pub fn disabled() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// The backoff delay before the given attempt (1-indexed: the delay
+    /// before the first retry is `delay_for_attempt(1)`).
+    pub(crate) fn delay_for_attempt(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(32);
+        let delay = self
+            .base_delay
+            .saturating_mul(1u32.checked_shl(exponent).unwrap_or(u32::MAX))
+            .min(self.max_delay);
+
+        if self.jitter {
+            let jitter_factor = 0.5 + js_sys::Math::random() * 0.5;
+            Duration::from_secs_f64(delay.as_secs_f64() * jitter_factor).min(self.max_delay)
+        } else {
+            delay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_per_attempt_and_caps_at_max_delay() {
+        let config = RetryConfig {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: false,
+        };
+
+        assert_eq!(config.delay_for_attempt(1), Duration::from_millis(100));
+        assert_eq!(config.delay_for_attempt(2), Duration::from_millis(200));
+        assert_eq!(config.delay_for_attempt(3), Duration::from_millis(400));
+        assert_eq!(config.delay_for_attempt(4), Duration::from_millis(800));
+        // Would keep doubling to 1600ms/3200ms/... without the cap.
+        assert_eq!(config.delay_for_attempt(10), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn disabled_config_never_retries() {
+        assert_eq!(RetryConfig::disabled().max_retries, 0);
+    }
+}